@@ -22,18 +22,47 @@ AATTGGCC
 aattGGCC
 
 Gaps are represented by RefSkip, unless
-Note that only primary alignments are preserved; output is written to stdout.
+By default only primary alignments are preserved; pass `--preserve-nonprimary` to also translate
+and keep secondary/supplementary alignments, which is where origin-spanning evidence from a
+split-aware aligner usually lives. Output is written to stdout, as SAM by default or as PAF via
+`--format paf`.
+
+A BAM may carry several duplicated contigs at once; each one's unduplicated length defaults to
+half of its `@SQ` `LN` value, and can be overridden with a repeated `--ref-len name=len` flag.
+
+Record translation is parallelized across a rayon worker pool sized by `--threads` (0, the
+default, uses all available cores); reading the BAM and writing the translated output each stay
+on their own dedicated thread, since `rust_htslib`'s `Reader` and `Writer` are not `Sync`.
+
+The coordinate liftover itself is also exposed as a standalone library API - [`lift_pos`] and
+[`lift_interval`] - for translating a single position or a BED-style interval without going
+through a BAM file at all.
  */
 
 extern crate log;
 use anyhow::Result;
 use log::debug;
-use rust_htslib::bam::record::{Cigar, CigarString, Record};
-use rust_htslib::bam::{Format, Header, Read, Reader, Writer};
+use rayon::prelude::*;
+use rust_htslib::bam::record::{Aux, Cigar, CigarString, Record};
+use rust_htslib::bam::{Format, Header, HeaderView, Read, Reader, Writer};
 use rust_htslib::htslib;
+use std::collections::HashMap;
 use std::error::Error;
+use std::fs;
+use std::io::Write as _;
 use std::iter;
 use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+/// output format for translated alignments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// the historical default: a SAM stream on stdout
+    Sam,
+    /// one PAF line per surviving record, for tools that consume PAF directly
+    Paf,
+}
 
 pub const NONPRIMARY: u16 = (htslib::BAM_FUNMAP
     | htslib::BAM_FSECONDARY
@@ -41,37 +70,670 @@ pub const NONPRIMARY: u16 = (htslib::BAM_FUNMAP
     | htslib::BAM_FDUP
     | htslib::BAM_FSUPPLEMENTARY) as u16;
 
-pub fn halve(ref_len: usize, bam_path: &PathBuf, use_del: bool) -> Result<()> {
+/// flags that always disqualify a record, regardless of `preserve_nonprimary` - unlike secondary
+/// and supplementary alignments, these never carry origin-spanning evidence worth keeping
+pub const ALWAYS_DROP: u16 =
+    (htslib::BAM_FUNMAP | htslib::BAM_FQCFAIL | htslib::BAM_FDUP) as u16;
+
+/// flags carried over verbatim from the source record, since `Record::set` only fills in
+/// qname/cigar/seq/qual
+const CARRIED_FLAGS: u16 = (htslib::BAM_FPAIRED
+    | htslib::BAM_FPROPER_PAIR
+    | htslib::BAM_FMUNMAP
+    | htslib::BAM_FREVERSE
+    | htslib::BAM_FMREVERSE
+    | htslib::BAM_FREAD1
+    | htslib::BAM_FREAD2
+    | htslib::BAM_FSECONDARY
+    | htslib::BAM_FSUPPLEMENTARY) as u16;
+
+/// number of records handed to the worker pool at a time; large enough to amortize the
+/// per-batch channel send/receive, small enough to keep writer latency reasonable
+const BATCH_SIZE: usize = 256;
+
+pub fn halve(
+    bam_path: &PathBuf,
+    use_del: bool,
+    split_supplementary: bool,
+    preserve_nonprimary: bool,
+    ref_path: Option<PathBuf>,
+    format: OutputFormat,
+    ref_len_overrides: HashMap<String, usize>,
+    threads: usize,
+) -> Result<()> {
     let reader: Reader = Reader::from_path(bam_path).unwrap();
     let hdr = reader.header();
+    let ref_lens = resolve_ref_lengths(hdr, &ref_len_overrides);
+    // `HeaderView` isn't `Send`/`Sync` (it wraps a raw `bam_hdr_t*`), so it can't be captured
+    // by the rayon worker closure below; resolve tid -> rname up front into a plain,
+    // `Send + Sync` `Vec` instead
+    let tid_names: Vec<String> = (0..hdr.target_count())
+        .map(|tid| String::from_utf8_lossy(hdr.tid2name(tid)).into_owned())
+        .collect();
+
+    let sam_writer = match format {
+        OutputFormat::Sam => Some(Writer::from_stdout(
+            &halved_header(hdr, &ref_lens)?,
+            Format::Sam,
+        )?),
+        OutputFormat::Paf => None,
+    };
+
+    let drop_mask = if preserve_nonprimary {
+        ALWAYS_DROP
+    } else {
+        NONPRIMARY
+    };
+    let reference = ref_path.map(|p| CircularRef::load(&p)).transpose()?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+
+    // reader thread: decodes records off the BAM file and ships them downstream in batches,
+    // since `Reader` is not `Sync` and must stay on a single thread
+    let (batch_tx, batch_rx) = mpsc::sync_channel::<Vec<Record>>(4);
+    let reader_bam_path = bam_path.clone();
+    let reader_handle = thread::spawn(move || {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        for aln in Reader::from_path(&reader_bam_path)
+            .unwrap()
+            .rc_records()
+            .map(|x| x.unwrap())
+        {
+            batch.push(aln);
+            if batch.len() == BATCH_SIZE {
+                if batch_tx.send(std::mem::take(&mut batch)).is_err() {
+                    return;
+                }
+            }
+        }
+        if !batch.is_empty() {
+            let _ = batch_tx.send(batch);
+        }
+    });
+
+    // writer thread: the flip side of the same constraint - `Writer` is not `Sync`, so all
+    // output is serialized back through it on one thread, in the order batches arrive
+    let (output_tx, output_rx) = mpsc::sync_channel::<Vec<RecordOutput>>(4);
+    let writer_handle = thread::spawn(move || -> Result<()> {
+        let mut sam_writer = sam_writer;
+        let stdout = std::io::stdout();
+        let mut paf_writer = stdout.lock();
+        for outputs in output_rx {
+            for out in outputs {
+                match out {
+                    RecordOutput::Dropped => {}
+                    RecordOutput::Sam(recs) => {
+                        let writer = sam_writer.as_mut().unwrap();
+                        for rec in recs {
+                            writer.write(&rec)?;
+                        }
+                    }
+                    RecordOutput::Paf(line) => writeln!(paf_writer, "{line}")?,
+                }
+            }
+        }
+        Ok(())
+    });
+
+    // main thread: pulls decoded batches off the reader, maps `mod_cigar` (by way of
+    // `translate_record`) over each batch in parallel on the worker pool, and forwards the
+    // per-record outputs - still in their original order - to the writer thread
+    for batch in batch_rx {
+        let outputs: Vec<RecordOutput> = pool.install(|| {
+            batch
+                .into_par_iter()
+                .map(|aln| {
+                    translate_record(
+                        &aln,
+                        &tid_names,
+                        &ref_lens,
+                        use_del,
+                        split_supplementary,
+                        drop_mask,
+                        format,
+                        reference.as_ref(),
+                    )
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+        if output_tx.send(outputs).is_err() {
+            break;
+        }
+    }
+    drop(output_tx);
+
+    reader_handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("reader thread panicked"))?;
+    writer_handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("writer thread panicked"))??;
+
+    Ok(())
+}
+
+/// the result of translating a single record: dropped outright, one or two SAM records
+/// (the second only when `--split-supplementary` divides an origin-spanning alignment), or
+/// one PAF line
+enum RecordOutput {
+    Dropped,
+    Sam(Vec<Record>),
+    Paf(String),
+}
+
+/// translate a single alignment into its output form; pure function of its inputs, so it is
+/// safe to call from any worker in the rayon pool
+fn translate_record(
+    aln: &Record,
+    tid_names: &[String],
+    ref_lens: &HashMap<String, usize>,
+    use_del: bool,
+    split_supplementary: bool,
+    drop_mask: u16,
+    format: OutputFormat,
+    reference: Option<&CircularRef>,
+) -> Result<RecordOutput> {
+    if aln.flags() & drop_mask != 0 {
+        return Ok(RecordOutput::Dropped);
+    }
 
-    let mut writer = Writer::from_stdout(&Header::from_template(&hdr), Format::Sam)?;
+    let rname = tid_names[aln.tid() as usize].clone();
+    let ref_len = *ref_lens.get(&rname).unwrap();
+    let seq = aln.seq().as_bytes();
+    let translated = mod_cigar(ref_len, aln.pos() as usize, aln.cigar().iter(), use_del);
 
-    for aln in Reader::from_path(bam_path)
-        .unwrap()
-        .rc_records()
-        .map(|x| x.unwrap())
-    {
-        if aln.flags() & NONPRIMARY == 0 {
-            let (new_start, mapped_cigar) =
-                mod_cigar(ref_len, aln.pos() as usize, aln.cigar().iter(), use_del);
+    if format == OutputFormat::Paf {
+        // PAF has no notion of the split-primary/supplementary pair, so a wrapping
+        // alignment is always rejoined via the same gapped CIGAR used historically; the gap
+        // itself is synthetic, though, so its reference span is tracked separately and kept
+        // out of the PAF stats below
+        let (pos, cigar, gap_occ, gap_nm) = match translated {
+            Translated::Simple(pos, cigar) => (pos, cigar, 0, 0),
+            Translated::Wrapped { suffix, prefix } => {
+                let gap_occ = ref_len as u32
+                    - ref_occupancy(prefix.1.iter())
+                    - ref_occupancy(suffix.1.iter());
+                let gap_nm = if use_del { gap_occ as i64 } else { 0 };
+                let (pos, cigar) = join_wrapped(ref_len, suffix, prefix, use_del);
+                (pos, cigar, gap_occ, gap_nm)
+            }
+        };
+        return Ok(RecordOutput::Paf(paf_line(
+            aln,
+            &rname,
+            ref_len,
+            pos,
+            &cigar,
+            &seq,
+            reference,
+            gap_occ,
+            gap_nm,
+        )));
+    }
 
+    let output = match translated {
+        Translated::Simple(new_start, mapped_cigar) => {
             let mut new_aln = Record::new();
             new_aln.set(
                 aln.qname(),
-                Some(&CigarString(mapped_cigar)),
-                aln.seq().as_bytes().as_slice(),
+                Some(&CigarString(mapped_cigar.clone())),
+                seq.as_slice(),
                 aln.qual(),
             );
+            new_aln.set_tid(aln.tid());
             new_aln.set_pos(new_start.try_into().unwrap());
+            carry_flags(aln, &mut new_aln);
+            carry_sa_tag(aln, &mut new_aln, &rname, ref_lens)?;
+            // a secondary/supplementary record may legitimately carry `SEQ = *`; there are no
+            // bases to recompute MD/NM against, so leave whatever tags it already had
+            if !seq.is_empty() {
+                if let Some(reference) = reference {
+                    recompute_tags(&mut new_aln, &mapped_cigar, new_start, &seq, &rname, reference)?;
+                }
+            }
+            RecordOutput::Sam(vec![new_aln])
+        }
+        Translated::Wrapped { suffix, prefix } if !split_supplementary => {
+            let (new_start, mapped_cigar) = join_wrapped(ref_len, suffix, prefix, use_del);
 
-            writer.write(&new_aln)?;
+            let mut new_aln = Record::new();
+            new_aln.set(
+                aln.qname(),
+                Some(&CigarString(mapped_cigar.clone())),
+                seq.as_slice(),
+                aln.qual(),
+            );
+            new_aln.set_tid(aln.tid());
+            new_aln.set_pos(new_start.try_into().unwrap());
+            carry_flags(aln, &mut new_aln);
+            carry_sa_tag(aln, &mut new_aln, &rname, ref_lens)?;
+            if !seq.is_empty() {
+                if let Some(reference) = reference {
+                    recompute_tags(&mut new_aln, &mapped_cigar, new_start, &seq, &rname, reference)?;
+                }
+            }
+            RecordOutput::Sam(vec![new_aln])
         }
+        Translated::Wrapped { suffix, prefix } => {
+            let (primary, supplementary) = split_records(aln, &rname, suffix, prefix, reference)?;
+            RecordOutput::Sam(vec![primary, supplementary])
+        }
+    };
+    Ok(output)
+}
+
+/// resolve each reference's unduplicated length: an explicit `name=len` override if given,
+/// otherwise half of the (duplicated) length recorded in the BAM header's `@SQ` lines
+fn resolve_ref_lengths(hdr: &HeaderView, overrides: &HashMap<String, usize>) -> HashMap<String, usize> {
+    (0..hdr.target_count())
+        .map(|tid| {
+            let name = String::from_utf8_lossy(hdr.tid2name(tid)).into_owned();
+            let len = overrides
+                .get(&name)
+                .copied()
+                .unwrap_or_else(|| hdr.target_len(tid).unwrap() as usize / 2);
+            (name, len)
+        })
+        .collect()
+}
+
+/// rewrite the header's `@SQ` `LN` fields to the resolved unduplicated lengths, leaving every
+/// other header line untouched
+fn halved_header(hdr: &HeaderView, ref_lens: &HashMap<String, usize>) -> Result<Header> {
+    let text = String::from_utf8(Header::from_template(hdr).to_bytes())?;
+    let mut corrected = String::with_capacity(text.len());
+    for line in text.lines() {
+        if line.starts_with("@SQ") {
+            let name = line
+                .split('\t')
+                .find_map(|f| f.strip_prefix("SN:"))
+                .expect("@SQ line without SN: tag");
+            let len = ref_lens[name];
+            let fields: Vec<String> = line
+                .split('\t')
+                .map(|f| match f.strip_prefix("LN:") {
+                    Some(_) => format!("LN:{}", len),
+                    None => f.to_string(),
+                })
+                .collect();
+            corrected.push_str(&fields.join("\t"));
+        } else {
+            corrected.push_str(line);
+        }
+        corrected.push('\n');
     }
+    Ok(Header::from_template(&HeaderView::from_bytes(
+        corrected.as_bytes(),
+    )))
+}
+
+/// build a PAF line for a translated alignment, reusing its `mod_cigar` result.
+///
+/// `gap_occ`/`gap_nm` describe the synthetic `RefSkip`/`Del` `join_wrapped` spliced in to
+/// bridge an origin-spanning alignment's two blocks: `gap_occ` is its reference length (0 for a
+/// non-wrapping alignment) and `gap_nm` is however much of that the gap op contributed to a
+/// reference-based NM computation (its full length when it's a `Del`, otherwise 0). Both are
+/// subtracted out so the PAF stats describe only the bases the read actually covers.
+fn paf_line(
+    aln: &Record,
+    rname: &str,
+    ref_len: usize,
+    pos: usize,
+    cigar: &[Cigar],
+    seq: &[u8],
+    reference: Option<&CircularRef>,
+    gap_occ: u32,
+    gap_nm: i64,
+) -> String {
+    let (clip_start, clip_end) = end_clips(cigar);
+    // `seq.len()` omits any hard-clipped bases (eg on a supplementary record), so the full
+    // query length has to be reconstructed from the cigar instead
+    let q_len = query_len(cigar.iter()) + total_hard_clip(cigar.iter());
+    // PAF query coordinates are always on the forward strand of the query; the cigar (and
+    // SEQ) are stored reference-forward, so a reverse-strand alignment needs its leading and
+    // trailing clips swapped back to the read's original orientation
+    let (q_start, q_end) = if aln.is_reverse() {
+        (clip_end, q_len - clip_start)
+    } else {
+        (clip_start, q_len - clip_end)
+    };
+    let strand = if aln.is_reverse() { '-' } else { '+' };
+
+    let t_occupancy = ref_occupancy(cigar.iter()) - gap_occ;
+    let t_start = pos as u32;
+    let t_end = t_start + t_occupancy;
+
+    let block_len = t_occupancy + total_ins(cigar.iter());
+    let nm = match reference {
+        Some(reference) => compute_md_nm(cigar, pos, seq, rname, reference).1 - gap_nm,
+        None => aln.aux(b"NM").map(|a| aux_to_i64(&a)).unwrap_or(0),
+    };
+    let matches = (block_len as i64 - nm).max(0);
 
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tcg:Z:{}",
+        String::from_utf8_lossy(aln.qname()),
+        q_len,
+        q_start,
+        q_end,
+        strand,
+        rname,
+        ref_len,
+        t_start,
+        t_end,
+        matches,
+        block_len,
+        aln.mapq(),
+        CigarString(cigar.to_vec()),
+    )
+}
+
+/// soft/hard clip lengths at the very start and end of a cigar string
+fn end_clips(cigar: &[Cigar]) -> (u32, u32) {
+    let clip_len = |op: &Cigar| match op {
+        Cigar::SoftClip(len) | Cigar::HardClip(len) => *len,
+        _ => 0,
+    };
+    let start = cigar.first().map(clip_len).unwrap_or(0);
+    let end = cigar.last().map(clip_len).unwrap_or(0);
+    (start, end)
+}
+
+/// total length of insertions in a cigar string - bases consumed by the query but not the reference
+fn total_ins<'a>(cigars: std::slice::Iter<'a, Cigar>) -> u32 {
+    cigars
+        .map(|c| match c {
+            Cigar::Ins(len) => *len,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// total length of hard clips in a cigar string - bases present in the original read but not
+/// stored in `SEQ`
+fn total_hard_clip<'a>(cigars: std::slice::Iter<'a, Cigar>) -> u32 {
+    cigars
+        .map(|c| match c {
+            Cigar::HardClip(len) => *len,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// the unduplicated sequence of every reference contig, each indexed modulo its own length so
+/// that a translated coordinate landing anywhere in a (formerly duplicated) contig resolves
+/// correctly
+struct CircularRef {
+    seqs: HashMap<String, Vec<u8>>,
+}
+
+impl CircularRef {
+    /// load a (possibly multi-contig) FASTA file, concatenating and upper-casing each record's bases
+    fn load(path: &PathBuf) -> Result<Self> {
+        let mut seqs: HashMap<String, Vec<u8>> = HashMap::new();
+        let mut current: Option<String> = None;
+        for line in fs::read_to_string(path)?.lines() {
+            if let Some(name) = line.strip_prefix('>') {
+                current = Some(name.split_whitespace().next().unwrap_or("").to_string());
+                seqs.entry(current.clone().unwrap()).or_default();
+            } else if let Some(name) = &current {
+                seqs.get_mut(name)
+                    .unwrap()
+                    .extend(line.trim().bytes().map(|b| b.to_ascii_uppercase()));
+            }
+        }
+        Ok(CircularRef { seqs })
+    }
+
+    fn base(&self, rname: &str, pos: usize) -> u8 {
+        let seq = &self.seqs[rname];
+        seq[pos % seq.len()]
+    }
+}
+
+/// recompute the `MD:Z:` and `NM:i:` tags for a translated record and write them onto it
+fn recompute_tags(
+    rec: &mut Record,
+    cigar: &[Cigar],
+    pos: usize,
+    seq: &[u8],
+    rname: &str,
+    reference: &CircularRef,
+) -> Result<()> {
+    let (md, nm) = compute_md_nm(cigar, pos, seq, rname, reference);
+    rec.push_aux(b"MD", Aux::String(&md))?;
+    rec.push_aux(b"NM", Aux::I32(nm as i32))?;
     Ok(())
 }
 
+/// walk a translated cigar against the reference and read, producing the `MD` string and
+/// edit distance (`NM`); factored out of [`recompute_tags`] so PAF output can reuse it
+fn compute_md_nm(
+    cigar: &[Cigar],
+    pos: usize,
+    seq: &[u8],
+    rname: &str,
+    reference: &CircularRef,
+) -> (String, i64) {
+    let mut md = String::new();
+    let mut run: u32 = 0;
+    let mut nm: i64 = 0;
+    let mut read_i: usize = 0;
+    let mut ref_i: usize = pos;
+
+    for op in cigar {
+        match op {
+            Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                for _ in 0..*len {
+                    let ref_base = reference.base(rname, ref_i);
+                    if seq[read_i].eq_ignore_ascii_case(&ref_base) {
+                        run += 1;
+                    } else {
+                        md.push_str(&run.to_string());
+                        md.push(ref_base as char);
+                        run = 0;
+                        nm += 1;
+                    }
+                    read_i += 1;
+                    ref_i += 1;
+                }
+            }
+            Cigar::Ins(len) => {
+                read_i += *len as usize;
+                nm += *len as i64;
+            }
+            Cigar::Del(len) => {
+                md.push_str(&run.to_string());
+                md.push('^');
+                for _ in 0..*len {
+                    md.push(reference.base(rname, ref_i) as char);
+                    ref_i += 1;
+                }
+                run = 0;
+                nm += *len as i64;
+            }
+            Cigar::RefSkip(len) => ref_i += *len as usize,
+            Cigar::SoftClip(len) => read_i += *len as usize,
+            Cigar::HardClip(_) | Cigar::Pad(_) => {}
+        }
+    }
+    md.push_str(&run.to_string());
+
+    (md, nm)
+}
+
+/// copy over the flags that `Record::set` doesn't - strand and pairing info, plus the
+/// secondary/supplementary bits, so translated non-primary alignments stay recognizable as such
+fn carry_flags(src: &Record, dst: &mut Record) {
+    dst.set_flags((dst.flags() & !CARRIED_FLAGS) | (src.flags() & CARRIED_FLAGS));
+}
+
+/// if the source record carries an `SA:Z:` tag (as a split-aware aligner writes for a
+/// primary/supplementary pair), translate each entry's reference position into unduplicated
+/// coordinates and write it onto the translated record
+fn carry_sa_tag(
+    src: &Record,
+    dst: &mut Record,
+    fallback_rname: &str,
+    ref_lens: &HashMap<String, usize>,
+) -> Result<()> {
+    if let Ok(Aux::String(sa)) = src.aux(b"SA") {
+        dst.push_aux(
+            b"SA",
+            Aux::String(&translate_sa_tag(sa, fallback_rname, ref_lens)),
+        )?;
+    }
+    Ok(())
+}
+
+/// translate the reference position of each `rname,pos,strand,cigar,mapq,nm;` entry in an
+/// `SA:Z:` tag into unduplicated coordinates, leaving the rest of each entry untouched. Each
+/// entry's own `rname` field selects its contig's length; `fallback_rname` covers malformed
+/// entries missing an `rname`.
+fn translate_sa_tag(sa: &str, fallback_rname: &str, ref_lens: &HashMap<String, usize>) -> String {
+    sa.split(';')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut fields: Vec<&str> = entry.split(',').collect();
+            let translated;
+            if fields.len() > 1 {
+                let rname = match fields[0] {
+                    "" => fallback_rname,
+                    rname => rname,
+                };
+                if let (Ok(pos), Some(&ref_len)) = (fields[1].parse::<usize>(), ref_lens.get(rname))
+                {
+                    translated = ((pos.saturating_sub(1) % ref_len) + 1).to_string();
+                    fields[1] = translated.as_str();
+                }
+            }
+            fields.join(",")
+        })
+        .map(|entry| entry + ";")
+        .collect()
+}
+
+/// build the primary (suffix block) and supplementary (prefix block) records for a read that
+/// bridges the plasmid origin, soft-clipping the bases covered by the other block and stamping
+/// each with an `SA:Z:` tag pointing at the other
+fn split_records(
+    aln: &Record,
+    rname: &str,
+    suffix: (usize, Vec<Cigar>),
+    prefix: (usize, Vec<Cigar>),
+    reference: Option<&CircularRef>,
+) -> Result<(Record, Record)> {
+    let (suffix_pos, suffix_cigar) = suffix;
+    let (prefix_pos, prefix_cigar) = prefix;
+    let seq = aln.seq().as_bytes();
+
+    let suffix_clip = query_len(prefix_cigar.iter());
+    let prefix_clip = query_len(suffix_cigar.iter());
+
+    let mut primary_cigar = suffix_cigar.clone();
+    primary_cigar.push(Cigar::SoftClip(suffix_clip));
+
+    let mut supplementary_cigar = vec![Cigar::SoftClip(prefix_clip)];
+    supplementary_cigar.extend(prefix_cigar.clone());
+
+    let strand = if aln.is_reverse() { '-' } else { '+' };
+    let nm = aln.aux(b"NM").map(|a| aux_to_i64(&a)).unwrap_or(0);
+    let mapq = aln.mapq();
+
+    let mut primary = Record::new();
+    primary.set(
+        aln.qname(),
+        Some(&CigarString(primary_cigar.clone())),
+        seq.as_slice(),
+        aln.qual(),
+    );
+    primary.set_tid(aln.tid());
+    primary.set_pos(suffix_pos.try_into().unwrap());
+    primary.set_mapq(mapq);
+    carry_flags(aln, &mut primary);
+    primary.push_aux(
+        b"SA",
+        Aux::String(&sa_tag(
+            rname,
+            prefix_pos,
+            strand,
+            &CigarString(supplementary_cigar.clone()),
+            mapq,
+            nm,
+        )),
+    )?;
+    // a secondary/supplementary record may legitimately carry `SEQ = *`; there are no bases
+    // to recompute MD/NM against, so leave whatever tags it already had
+    if !seq.is_empty() {
+        if let Some(reference) = reference {
+            recompute_tags(
+                &mut primary,
+                &primary_cigar,
+                suffix_pos,
+                &seq,
+                rname,
+                reference,
+            )?;
+        }
+    }
+
+    let mut supplementary = Record::new();
+    supplementary.set(
+        aln.qname(),
+        Some(&CigarString(supplementary_cigar.clone())),
+        seq.as_slice(),
+        aln.qual(),
+    );
+    supplementary.set_tid(aln.tid());
+    supplementary.set_pos(prefix_pos.try_into().unwrap());
+    supplementary.set_mapq(mapq);
+    carry_flags(aln, &mut supplementary);
+    supplementary.set_flags(supplementary.flags() | htslib::BAM_FSUPPLEMENTARY as u16);
+    supplementary.push_aux(
+        b"SA",
+        Aux::String(&sa_tag(
+            rname,
+            suffix_pos,
+            strand,
+            &CigarString(primary_cigar),
+            mapq,
+            nm,
+        )),
+    )?;
+    if !seq.is_empty() {
+        if let Some(reference) = reference {
+            recompute_tags(
+                &mut supplementary,
+                &supplementary_cigar,
+                prefix_pos,
+                &seq,
+                rname,
+                reference,
+            )?;
+        }
+    }
+
+    Ok((primary, supplementary))
+}
+
+/// format an `SA:Z:` entry, including its trailing `;`
+fn sa_tag(rname: &str, pos: usize, strand: char, cigar: &CigarString, mapq: u8, nm: i64) -> String {
+    format!("{},{},{},{},{},{};", rname, pos + 1, strand, cigar, mapq, nm)
+}
+
+fn aux_to_i64(aux: &Aux) -> i64 {
+    match aux {
+        Aux::I8(v) => *v as i64,
+        Aux::U8(v) => *v as i64,
+        Aux::I16(v) => *v as i64,
+        Aux::U16(v) => *v as i64,
+        Aux::I32(v) => *v as i64,
+        Aux::U32(v) => *v as i64,
+        _ => 0,
+    }
+}
+
 /// return a new cigar w/ the same type as ref_cig
 fn new_cigar(ref_cig: &Cigar, len: u32) -> Cigar {
     match ref_cig {
@@ -101,19 +763,94 @@ fn ref_occupancy<'a>(mut cigars: std::slice::Iter<'a, Cigar>) -> u32 {
         .sum()
 }
 
+/// length of query consumed by cigar string - eg, Del(N) doesn't consume any query
+fn query_len<'a>(cigars: std::slice::Iter<'a, Cigar>) -> u32 {
+    cigars
+        .map(|c| match c {
+            Cigar::Match(len) => *len,
+            Cigar::Ins(len) => *len,
+            Cigar::SoftClip(len) => *len,
+            Cigar::Equal(len) => *len,
+            Cigar::Diff(len) => *len,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// outcome of translating a (possibly origin-spanning) cigar string into unduplicated coordinates
+#[derive(Debug, PartialEq)]
+pub enum Translated {
+    /// alignment was entirely within one copy of the reference; no split was needed
+    Simple(usize, Vec<Cigar>),
+    /// alignment bridged the origin and was divided into its two constituent blocks
+    Wrapped {
+        /// the block covering the tail of the reference, at its translated start position
+        suffix: (usize, Vec<Cigar>),
+        /// the block that wrapped around to the head of the reference, at position 0
+        prefix: (usize, Vec<Cigar>),
+    },
+}
+
+/// join a wrapped alignment's two blocks back into a single gapped cigar, as plasmod has
+/// historically done when `--split-supplementary` is not requested
+fn join_wrapped(
+    ref_len: usize,
+    suffix: (usize, Vec<Cigar>),
+    prefix: (usize, Vec<Cigar>),
+    use_del: bool,
+) -> (usize, Vec<Cigar>) {
+    let (_, suffix_cigar) = suffix;
+    let (_, prefix_cigar) = prefix;
+
+    let end_of_prefix = ref_occupancy(prefix_cigar.iter());
+    let len_of_suffix = ref_occupancy(suffix_cigar.iter());
+    let gap_len =
+        <usize as TryInto<u32>>::try_into(ref_len).unwrap() - end_of_prefix - len_of_suffix;
+    let gap = if use_del {
+        Cigar::Del(gap_len)
+    } else {
+        Cigar::RefSkip(gap_len)
+    };
+    (
+        0,
+        prefix_cigar
+            .into_iter()
+            .chain(iter::once(gap))
+            .chain(suffix_cigar.into_iter())
+            .collect(),
+    )
+}
+
+/// lift a single 0-based duplicated-space reference position into its unduplicated equivalent
+pub fn lift_pos(ref_len: usize, pos: usize) -> usize {
+    pos % ref_len
+}
+
+/// lift a half-open duplicated-space reference interval `[start, end)` into its unduplicated
+/// equivalent, returning one interval normally or two pieces when it straddles the origin
+pub fn lift_interval(ref_len: usize, start: usize, end: usize) -> Vec<(usize, usize)> {
+    let lifted_start = lift_pos(ref_len, start);
+    let len = end - start;
+    if lifted_start + len <= ref_len {
+        vec![(lifted_start, lifted_start + len)]
+    } else {
+        vec![(lifted_start, ref_len), (0, lifted_start + len - ref_len)]
+    }
+}
+
 /// apply "modula" to cigar string
 fn mod_cigar<'a>(
     ref_len: usize,
     aln_pos: usize,
     mut cigars: std::slice::Iter<'a, Cigar>,
     use_del: bool,
-) -> (usize, Vec<Cigar>) {
-    let mut pos: usize = aln_pos % ref_len;
+) -> Translated {
+    let mut pos: usize = lift_pos(ref_len, aln_pos);
 
     // if the alignment starts in the second copy, then the cigar string can
     // be used w/out modification, and only the start position needs to change
     if aln_pos >= ref_len {
-        return (pos, cigars.cloned().collect());
+        return Translated::Simple(pos, cigars.cloned().collect());
     }
 
     // for operations that land in the first reference copy
@@ -125,53 +862,143 @@ fn mod_cigar<'a>(
     // we want to divide the cigar operations, stashing those from the first half
     // in @suffix, and those from the second half in @prefix
     for op in cigars {
+        let op_ref_len = ref_occupancy(std::slice::from_ref(op).iter()) as usize;
         if pos >= ref_len {
             prefix.push(op.clone());
-        } else if (pos + op.len() as usize) < ref_len {
+        } else if pos + op_ref_len <= ref_len {
+            // either the op stays within the first copy, or (op_ref_len == 0, eg an
+            // Ins/SoftClip) it consumes no reference and can't straddle the boundary at all
             suffix.push(op.clone());
         } else {
             // this operation spans the boundary; cut it
-            let first_half = ref_len - pos;
-            let second_half = op.len() as usize - first_half;
-            suffix.push(new_cigar(&op, first_half as u32));
-            prefix.push(new_cigar(&op, second_half as u32));
+            let pieces = lift_interval(ref_len, pos, pos + op_ref_len);
+            let first_half = (pieces[0].1 - pieces[0].0) as u32;
+            let second_half = (pieces[1].1 - pieces[1].0) as u32;
+            suffix.push(new_cigar(&op, first_half));
+            prefix.push(new_cigar(&op, second_half));
         }
-        pos += ref_occupancy(vec![op.clone()].iter()) as usize;
+        pos += op_ref_len;
     }
 
     if prefix.len() == 0 {
         // trivial case - everything landed in the first copy of the reference
-        return (aln_pos % ref_len, suffix);
+        return Translated::Simple(lift_pos(ref_len, aln_pos), suffix);
     }
 
-    let end_of_prefix = ref_occupancy(prefix.iter());
-    let len_of_suffix = ref_occupancy(suffix.iter());
-    let gap_len =
-        <usize as TryInto<u32>>::try_into(ref_len).unwrap() - end_of_prefix - len_of_suffix;
-    let gap = if use_del {
-        Cigar::Del(gap_len)
-    } else {
-        Cigar::RefSkip(gap_len)
-    };
-    (
-        0,
-        prefix
-            .into_iter()
-            .chain(iter::once(gap))
-            .chain(suffix.into_iter())
-            .collect(),
-    )
+    let suffix_start = lift_pos(ref_len, aln_pos);
+    Translated::Wrapped {
+        suffix: (suffix_start, suffix),
+        prefix: (0, prefix),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_paf_line() {
+        let cigar = vec![Cigar::SoftClip(3), Cigar::Match(10), Cigar::SoftClip(2)];
+        let seq = b"AAAAAAAAAAAAAAA".to_vec(); // 15bp: 3 clip + 10 match + 2 clip
+
+        let mut aln = Record::new();
+        aln.set(b"read1", Some(&CigarString(cigar.clone())), &seq, &[30; 15]);
+        aln.set_mapq(42);
+
+        assert_eq!(
+            "read1\t15\t3\t13\t+\tplasmid\t100\t20\t30\t10\t10\t42\tcg:Z:3S10M2S",
+            paf_line(&aln, "plasmid", 100, 20, &cigar, &seq, None, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_paf_line_reverse_strand_swaps_query_clips() {
+        // leading/trailing clips are in reference-forward order; on the reverse strand the
+        // query-forward start/end must come from the trailing/leading clip instead
+        let cigar = vec![Cigar::SoftClip(3), Cigar::Match(10), Cigar::SoftClip(2)];
+        let seq = b"AAAAAAAAAAAAAAA".to_vec();
+
+        let mut aln = Record::new();
+        aln.set(b"read1", Some(&CigarString(cigar.clone())), &seq, &[30; 15]);
+        aln.set_mapq(42);
+        aln.set_reverse();
+
+        assert_eq!(
+            "read1\t15\t2\t12\t-\tplasmid\t100\t20\t30\t10\t10\t42\tcg:Z:3S10M2S",
+            paf_line(&aln, "plasmid", 100, 20, &cigar, &seq, None, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_paf_line_excludes_wrapped_gap() {
+        // a wrapped alignment's joined cigar carries a synthetic gap spanning almost the
+        // whole reference; the PAF stats should only reflect the two real blocks
+        let cigar = vec![Cigar::Match(10), Cigar::RefSkip(80), Cigar::Match(10)];
+        let seq = b"AAAAAAAAAAAAAAAAAAAA".to_vec(); // 20bp, all matched
+
+        let mut aln = Record::new();
+        aln.set(b"read1", Some(&CigarString(cigar.clone())), &seq, &[30; 20]);
+        aln.set_mapq(42);
+
+        assert_eq!(
+            "read1\t20\t0\t20\t+\tplasmid\t100\t0\t20\t20\t20\t42\tcg:Z:10M80N10M",
+            paf_line(&aln, "plasmid", 100, 0, &cigar, &seq, None, 80, 0)
+        );
+    }
+
+    #[test]
+    fn test_paf_line_hard_clipped_query_len() {
+        // a supplementary record's SEQ only covers the aligned+soft-clipped portion; the
+        // hard-clipped bases are absent from SEQ but must still count toward q_len
+        let cigar = vec![Cigar::HardClip(5), Cigar::Match(10), Cigar::SoftClip(2)];
+        let seq = b"AAAAAAAAAAAA".to_vec(); // 12bp: 10 match + 2 clip (5 hard-clipped bases excluded)
+
+        let mut aln = Record::new();
+        aln.set(b"read1", Some(&CigarString(cigar.clone())), &seq, &[30; 12]);
+        aln.set_mapq(42);
+
+        assert_eq!(
+            "read1\t17\t5\t15\t+\tplasmid\t100\t20\t30\t10\t10\t42\tcg:Z:5H10M2S",
+            paf_line(&aln, "plasmid", 100, 20, &cigar, &seq, None, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_recompute_tags_mismatch_and_deletion() {
+        // ref: AAAACCCCGGGG (len 12); read matches the first 4, mismatches base 5 (C->T),
+        // deletes 2 ref bases, then mismatches again before a final match
+        let reference = CircularRef {
+            seqs: HashMap::from([("plasmid".to_string(), b"AAAACCCCGGGG".to_vec())]),
+        };
+        let cigar = vec![Cigar::Match(5), Cigar::Del(2), Cigar::Match(2)];
+        let seq = b"AAAATGG".to_vec();
+
+        let mut rec = Record::new();
+        rec.set(b"q", Some(&CigarString(cigar.clone())), &seq, &[30; 7]);
+        recompute_tags(&mut rec, &cigar, 0, &seq, "plasmid", &reference).unwrap();
+
+        assert_eq!(Aux::String("4C0^CC0C1"), rec.aux(b"MD").unwrap());
+        assert_eq!(Aux::I32(4), rec.aux(b"NM").unwrap());
+    }
+
+    #[test]
+    fn test_translate_sa_tag() {
+        let ref_lens = HashMap::from([("plasmid".to_string(), 100)]);
+        assert_eq!(
+            "plasmid,91,+,10M,60,0;plasmid,1,-,5S10M,60,1;",
+            translate_sa_tag(
+                "plasmid,191,+,10M,60,0;plasmid,101,-,5S10M,60,1;",
+                "plasmid",
+                &ref_lens
+            )
+        );
+    }
+
     #[test]
     fn test_simplest_case() {
         // ref seq is 100bp; alignment is simple 10bp match at start
         assert_eq!(
-            (0, vec![Cigar::Match(10)]),
+            Translated::Simple(0, vec![Cigar::Match(10)]),
             mod_cigar(100, 0, vec![Cigar::Match(10)].iter(), true)
         );
     }
@@ -181,19 +1008,32 @@ mod tests {
         // ref seq is 100bp; alignment is simple 10bp match starting at position 100,
         // ie, entirely in the second duplicate
         assert_eq!(
-            (0, vec![Cigar::Match(10)]),
+            Translated::Simple(0, vec![Cigar::Match(10)]),
             mod_cigar(100, 100, vec![Cigar::Match(10)].iter(), true)
         );
     }
 
     #[test]
     fn test_spanning() {
+        assert_eq!(
+            Translated::Wrapped {
+                suffix: (90, vec![Cigar::Match(10)]),
+                prefix: (0, vec![Cigar::Match(10)]),
+            },
+            mod_cigar(100, 90, vec![Cigar::Match(20)].iter(), false)
+        );
+        // historical behaviour, preserved via join_wrapped for non-split callers
         assert_eq!(
             (
                 0,
                 vec![Cigar::Match(10), Cigar::RefSkip(80), Cigar::Match(10)]
             ),
-            mod_cigar(100, 90, vec![Cigar::Match(20)].iter(), false)
+            join_wrapped(
+                100,
+                (90, vec![Cigar::Match(10)]),
+                (0, vec![Cigar::Match(10)]),
+                false
+            )
         );
     }
 
@@ -201,23 +1041,22 @@ mod tests {
     fn test_spanning_del() {
         assert_eq!(
             (0, vec![Cigar::Match(10), Cigar::Del(80), Cigar::Match(10)]),
-            mod_cigar(100, 90, vec![Cigar::Match(20)].iter(), true)
+            join_wrapped(
+                100,
+                (90, vec![Cigar::Match(10)]),
+                (0, vec![Cigar::Match(10)]),
+                true
+            )
         );
     }
 
     #[test]
     fn test_mix() {
         assert_eq!(
-            (
-                0,
-                vec![
-                    Cigar::Match(10),
-                    Cigar::RefSkip(40),
-                    Cigar::Match(20),
-                    Cigar::Ins(20),
-                    Cigar::Match(30)
-                ]
-            ),
+            Translated::Wrapped {
+                suffix: (50, vec![Cigar::Match(20), Cigar::Ins(20), Cigar::Match(30)]),
+                prefix: (0, vec![Cigar::Match(10)]),
+            },
             mod_cigar(
                 100,
                 50,
@@ -226,4 +1065,24 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_lift_pos() {
+        assert_eq!(5, lift_pos(100, 5));
+        assert_eq!(5, lift_pos(100, 105));
+        assert_eq!(0, lift_pos(100, 100));
+    }
+
+    #[test]
+    fn test_lift_interval() {
+        // entirely within one copy
+        assert_eq!(vec![(10, 20)], lift_interval(100, 10, 20));
+        // entirely within the second copy
+        assert_eq!(vec![(10, 20)], lift_interval(100, 110, 120));
+        // straddles the origin, so it's split into two pieces
+        assert_eq!(
+            vec![(90, 100), (0, 10)],
+            lift_interval(100, 90, 110)
+        );
+    }
 }
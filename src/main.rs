@@ -1,6 +1,7 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::Parser;
 use plasmod::*;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
@@ -12,13 +13,66 @@ struct Cli {
         help = "use delete operator instead of refskip to fill in gaps"
     )]
     use_del: bool,
-    ref_len: usize,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "emit origin-spanning reads as split primary+supplementary records with SA tags, instead of one gapped CIGAR"
+    )]
+    split_supplementary: bool,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "translate and keep secondary/supplementary alignments instead of discarding them"
+    )]
+    preserve_nonprimary: bool,
+    #[arg(
+        long = "ref",
+        help = "unduplicated plasmid FASTA, used to recompute MD/NM tags after translation"
+    )]
+    ref_fasta: Option<PathBuf>,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "sam",
+        help = "output format; split-supplementary has no effect on paf output"
+    )]
+    format: OutputFormat,
+    #[arg(
+        long = "ref-len",
+        value_parser = parse_ref_len,
+        help = "override an @SQ contig's unduplicated length, as name=len; repeatable. By default \
+                each contig's length is taken to be half of its (duplicated) @SQ LN"
+    )]
+    ref_lens: Vec<(String, usize)>,
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "worker threads used to translate records in parallel; 0 uses all available cores"
+    )]
+    threads: usize,
     bam_path: PathBuf,
 }
 
+/// parse a `name=len` CLI argument into its constituent parts
+fn parse_ref_len(s: &str) -> Result<(String, usize)> {
+    let (name, len) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected name=len, got {s:?}"))?;
+    Ok((name.to_string(), len.parse()?))
+}
+
 fn main() -> Result<()> {
     env_logger::init();
     let args = Cli::parse();
-    halve(args.ref_len, &args.bam_path, args.use_del)?;
+    halve(
+        &args.bam_path,
+        args.use_del,
+        args.split_supplementary,
+        args.preserve_nonprimary,
+        args.ref_fasta,
+        args.format,
+        args.ref_lens.into_iter().collect::<HashMap<_, _>>(),
+        args.threads,
+    )?;
     Ok(())
 }